@@ -0,0 +1,143 @@
+use alloc::string::String;
+
+use ostd::Pod;
+
+/// `VIRTIO_SND_CTL_TYPE_XXX`: the kind of value a control element holds.
+pub mod ctl_type {
+    pub const BOOLEAN: u32 = 1;
+    pub const INTEGER: u32 = 2;
+    pub const INTEGER64: u32 = 3;
+    pub const ENUMERATED: u32 = 4;
+    pub const BYTES: u32 = 5;
+    pub const IEC958: u32 = 6;
+}
+
+/// Maximum number of scalar channels carried by a single `CtlRead`/`CtlWrite` value,
+/// mirroring ALSA's notion of a per-channel control (e.g. stereo volume).
+pub const CTL_MAX_CHANNELS: usize = 4;
+
+/// Parsed, ergonomic view of a `virtio_snd_ctl_info` entry.
+#[derive(Debug, Clone)]
+pub struct SndCtlInfo {
+    pub control_id: u32,
+    pub item_type: u32,
+    pub access: u32,
+    pub count: u32,
+    pub min: i32,
+    pub max: i32,
+    pub step: u32,
+    pub name: String,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlHdr {
+    pub hdr: u32,        // 通用信息头
+    pub control_id: u32, // 小端：控制元素 ID
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlQueryInfo {
+    pub hdr: u32,      // 通用信息头
+    pub start_id: u32, // 小端：起始 ID
+    pub count: u32,    // 小端：查询的条目数量
+    pub size: u32,     // 小端：每个条目的大小
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlInfoRaw {
+    pub hdr: u32,         // 嵌套的通用信息头
+    pub control_id: u32,  // 小端：控制元素 ID
+    pub item_type: u32,   // 小端：值类型 (VIRTIO_SND_CTL_TYPE_XXX)
+    pub access: u32,      // 小端：访问权限位掩码 (读/写/易变)
+    pub count: u32,       // 小端：值的数量（如声道数）
+    pub min: i32,         // 小端：整型控制的最小值
+    pub max: i32,         // 小端：整型控制的最大值
+    pub step: u32,        // 小端：整型控制的步进
+    pub name: [u8; 44],   // 控制元素名称，以 NUL 结尾
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlEnumItemsQuery {
+    pub hdr: SndCtlHdr, // control_id 所在的通用头
+    pub item_id: u32,   // 小端：要查询的枚举项下标
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlEnumItemRaw {
+    pub name: [u8; 64], // 枚举项名称，以 NUL 结尾
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlValue {
+    pub value: [i32; CTL_MAX_CHANNELS], // 每个声道的标量值
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlWriteRequest {
+    pub hdr: SndCtlHdr,
+    pub value: SndCtlValue,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndCtlTlvHdr {
+    pub hdr: SndCtlHdr, // control_id 所在的通用头
+    pub size: u32,      // 小端：随附的 TLV 负载大小
+}
+
+pub(super) const CTL_HDR_SIZE: usize = size_of::<SndCtlHdr>();
+pub(super) const CTL_QUERY_INFO_SIZE: usize = size_of::<SndCtlQueryInfo>();
+pub(super) const CTL_INFO_SIZE: usize = size_of::<SndCtlInfoRaw>();
+pub(super) const CTL_ENUM_ITEMS_QUERY_SIZE: usize = size_of::<SndCtlEnumItemsQuery>();
+pub(super) const CTL_ENUM_ITEM_SIZE: usize = size_of::<SndCtlEnumItemRaw>();
+pub(super) const CTL_VALUE_SIZE: usize = size_of::<SndCtlValue>();
+pub(super) const CTL_WRITE_REQUEST_SIZE: usize = size_of::<SndCtlWriteRequest>();
+pub(super) const CTL_TLV_HDR_SIZE: usize = size_of::<SndCtlTlvHdr>();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ctl_info_raw_matches_wire_layout() {
+        // `virtio_snd_ctl_info`: 7 x u32/i32 + name[44] = 28 + 44 = 72 bytes.
+        assert_eq!(size_of::<SndCtlInfoRaw>(), 72);
+    }
+
+    #[test]
+    fn ctl_value_matches_wire_layout() {
+        assert_eq!(size_of::<SndCtlValue>(), CTL_MAX_CHANNELS * size_of::<i32>());
+    }
+
+    #[test]
+    fn ctl_info_raw_parses_after_status_hdr_skip() {
+        let mut name = [0u8; 44];
+        name[..4].copy_from_slice(b"Mic\0");
+        let raw = SndCtlInfoRaw {
+            hdr: 0,
+            control_id: 7,
+            item_type: ctl_type::BOOLEAN,
+            access: 1,
+            count: 1,
+            min: 0,
+            max: 1,
+            step: 0,
+            name,
+        };
+
+        let mut buf = [0u8; 4 + size_of::<SndCtlInfoRaw>()];
+        buf[4..].copy_from_slice(raw.as_bytes());
+
+        let parsed = SndCtlInfoRaw::from_bytes(&buf[4..]);
+        assert_eq!(parsed.control_id, raw.control_id);
+        assert_eq!(parsed.item_type, raw.item_type);
+        assert_eq!(&parsed.name[..4], b"Mic\0");
+    }
+}
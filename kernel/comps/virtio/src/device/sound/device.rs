@@ -1,368 +1,1145 @@
-use alloc::boxed::Box;
-use alloc::sync::Arc;
-use core::hint::spin_loop;
-use log::debug;
-use ostd::{Pod};
-use ostd::early_println;
-use ostd::mm::{DmaDirection, DmaStream, DmaStreamSlice, FrameAllocOptions, Infallible, VmIo, VmReader};
-use ostd::sync::{LocalIrqDisabled, SpinLock, SpinLockGuard};
-use ostd::trap::TrapFrame;
-use crate::device::sound::config::{MessageHdr, SoundFeatures, VirtioSoundConfig, PcmFeatures, PcmFormats, PcmFrameRates};
-use crate::device::VirtioDeviceError;
-use crate::queue::VirtQueue;
-use crate::transport::{ConfigManager, VirtioTransport};
-
-
-pub type SoundCallback = dyn Fn(VmReader<Infallible>) + Send + Sync;
-
-pub struct SoundDevice {
-    config_manager: ConfigManager<VirtioSoundConfig>,
-    transport: SpinLock<Box<dyn VirtioTransport>>,
-    queue: SpinLock<VirtQueue>,
-    txq: SpinLock<VirtQueue>,
-    rxq: SpinLock<VirtQueue>,
-    tx_buffer: DmaStream,
-    rx_buffer: DmaStream,
-    ctl_buffer: DmaStream,
-    event_buffer: DmaStream,
-    // callbacks: RwLock<Vec<&'static SoundCallback>, LocalIrqDisabled>,
-}
-
-impl SoundDevice {
-    pub(crate) fn negotiate_features(features: u64) -> u64 {
-        // let features =SoundFeatures::from_bits_truncate(features);
-        // features.bits()
-        features
-    }
-
-    pub fn init(mut transport: Box<dyn VirtioTransport>) -> Result<(), VirtioDeviceError> {
-        let config_manager = VirtioSoundConfig::new_manager(transport.as_ref());
-        let config = config_manager.read_config();
-        debug!("virtio_sound_config = {:?}", config);
-
-        debug!("begin initializing virtqueues");
-        const Q_INDEX: u16 = 0;
-        const TXQ_INDEX: u16 = 1;
-        const RXQ_INDEX: u16 = 2;
-
-        let message_queue = SpinLock::new(VirtQueue::new(Q_INDEX, 2, transport.as_mut()).unwrap());
-        let txq = SpinLock::new(VirtQueue::new(TXQ_INDEX, 2, transport.as_mut()).unwrap());
-        let rxq = SpinLock::new(VirtQueue::new(RXQ_INDEX, 2, transport.as_mut()).unwrap());
-
-
-        let tx_buffer = {
-            let vm_segment = FrameAllocOptions::new().alloc_segment(4).unwrap();
-            DmaStream::map(vm_segment.into(), DmaDirection::ToDevice, false).unwrap()
-        };
-
-        let rx_buffer = {
-            let vm_segment = FrameAllocOptions::new().alloc_segment(4).unwrap();
-            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
-        };
-
-        let ctl_buffer = {
-            let vm_segment = FrameAllocOptions::new().alloc_segment(100).unwrap();
-            DmaStream::map(vm_segment.into(), DmaDirection::ToDevice, false).unwrap()
-        };
-
-        let event_buffer = {
-            let vm_segment = FrameAllocOptions::new().alloc_segment(100).unwrap();
-            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
-        };
-
-        let device = Arc::new(
-            Self {
-                config_manager,
-                transport: SpinLock::new(transport),
-                queue: message_queue,
-                txq,
-                rxq,
-                tx_buffer,
-                rx_buffer,
-                ctl_buffer,
-                event_buffer,
-                // callbacks: RwLock::new(Vec::new()),
-            });
-
-
-        // Register irq callbacks
-        let mut transport = device.transport.disable_irq().lock();
-
-        fn config_space_change(_: &TrapFrame) {
-            debug!("sound device config space change");
-        }
-
-        transport
-            .register_cfg_callback(Box::new(config_space_change))
-            .unwrap();
-
-        transport.finish_init();
-        drop(transport);
-
-
-        Self::test_device(&*device);
-        Ok(())
-    }
-
-    pub fn handle_event_irq(&self) {
-        debug!("handling event irq");
-        self.event_buffer.sync(0..PCM_INFO_SIZE).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let _features = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let _formats = self.event_buffer.reader().unwrap().read_once::<u64>().unwrap();
-        let _rates = self.event_buffer.reader().unwrap().read_once::<u64>().unwrap();
-        let _direction = self.event_buffer.reader().unwrap().read_once::<u8>().unwrap();
-        let _channel_min = self.event_buffer.reader().unwrap().read_once::<u8>().unwrap();
-        let _channel_max = self.event_buffer.reader().unwrap().read_once::<u8>().unwrap();
-        debug!(
-            "Event IRQ handled: hdr={:?}", hdr
-        );
-    }
-
-
-    fn handle_rx_irq(&self) {
-        // TODO!
-    }
-
-    fn test_device(&self) {
-        // Query supported configuration
-        let mut queue = self.queue.disable_irq().lock();
-        early_println!("Query PCM info");
-        let req = SndPcmQueryInfo {
-            hdr: MessageHdr::PcmInfo as u32,
-            start_id: 0,
-            count: 1,
-            size: PCM_INFO_QUERY_SIZE as u32,
-        };
-        self.send(&req, PCM_INFO_QUERY_SIZE, PCM_INFO_SIZE, &mut queue);
-        self.event_buffer.sync(0..PCM_INFO_SIZE).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let features = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let formats = self.event_buffer.reader().unwrap().read_once::<u64>().unwrap();
-        let rates = self.event_buffer.reader().unwrap().read_once::<u64>().unwrap();
-        let direction = self.event_buffer.reader().unwrap().read_once::<u8>().unwrap();
-        let channel_min = self.event_buffer.reader().unwrap().read_once::<u8>().unwrap();
-        let channel_max = self.event_buffer.reader().unwrap().read_once::<u8>().unwrap();
-        early_println!(
-            "Query PCM info: hdr={:?}, features={:?}, formats={:?}, rates={:?}, direction={:?}, channel_min={:?}, channel_max={:?}",
-            hdr, features, formats, rates, direction, channel_min, channel_max
-        );
-        // Query PCM info: hdr=32768, features=32768, formats=32768, rates=32768, direction=0, channel_min=0, channel_max=0
-        
-        // --------------------------------------------------------------------------------------
-        //流程顺序：SetParams -> Prepared -> Start -> IO Message -> Stop >> Release
-
-        early_println!("Set PCM params");
-        let req = SndPcmSetParams {
-            hdr: MessageHdr::PcmSetParams as u32,
-            buffer_bytes: 1,
-            period_bytes: 1,
-            features: 0, 
-            channels: 0,
-            format: PcmFormats::FmtU8 as u8,
-            rate: PcmFrameRates::Rate16000 as u8,
-            padding: [0, 0, 0, 0, 0],
-        };//提示:Number of channels is not supported
-        // let req = SndPcmSetParams {
-        //     hdr: MessageHdr::PcmSetParams as u32,
-        //     buffer_bytes: 20, // ??
-        //     period_bytes: 10, // 2 bytes * 5
-        //     features: 1 << (PcmFeatures::MsgPolling as u32), 
-        //     channels: 0,
-        //     format: PcmFormats::FmtU16 as u8,
-        //     rate: PcmFrameRates::Rate16000 as u8,
-        //     padding: [0, 0, 0, 0, 0],
-        // };对于单声道16bit采样率16000Hz的音频,但会报错Streams have not been initialized并卡死
-        self.send(&req, PCM_SET_PARAMS_SIZE, 8, &mut queue);
-        self.event_buffer.sync(0..8).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let data = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        early_println!(
-            "Response of setting params: hdr={:?}, data={:?}", hdr, data
-        );
-
-        // --------------------------------------------------------------------------------------
-        
-        early_println!("Set PCM prepared");
-        let req = SndPcmHdr {
-            hdr: MessageHdr::PcmPrepare as u32,
-            stream_id: 0,
-        };
-        self.send(&req, PCM_HDR_SIZE, 8, &mut queue);
-        self.event_buffer.sync(0..8).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let data = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        early_println!(
-            "Response of setting preparation: hdr={:?}, data={:?}", hdr, data
-        );
-
-        // --------------------------------------------------------------------------------------
-        
-        early_println!("Set PCM start");
-        let req = SndPcmHdr {
-            hdr: MessageHdr::PcmStart as u32,
-            stream_id: 0,
-        };
-        self.send(&req, PCM_HDR_SIZE, 8, &mut queue);
-        self.event_buffer.sync(0..8).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let data = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        early_println!(
-            "Response of setting start: hdr={:?}, data={:?}", hdr, data
-        );
-
-        // --------------------------------------------------------------------------------------
-        
-        // early_println!("Send PCM frames");//panic: InvalidArgs
-        // let tx_slice = {
-        //     let txq_slice =
-        //         DmaStreamSlice::new(self.tx_buffer.clone(), 0, 5);//14);//2*5+4
-        //     let req = SndPcmIOMessage {
-        //         stream_id: 0 as u32,   
-        //         buffer: 1,//[42, 42, 42, 42, 42],//array as [u16; 5],
-        //     };
-        //     txq_slice.write_val(0, &req).unwrap();
-        //     txq_slice.sync().unwrap();
-        //     txq_slice
-        // };
-        // let rx_slice = {
-        //     let rx_slice =
-        //         DmaStreamSlice::new(self.rx_buffer.clone(), 0, 8);
-        //     rx_slice
-        // };
-        // let mut queue = self.queue.disable_irq().lock();
-        // queue
-        //     .add_dma_buf(&[&tx_slice], &[&rx_slice])
-        //     .expect("add queue failed");
-        // if queue.should_notify() {
-        //     queue.notify();
-        // }
-        // while !queue.can_pop() {
-        //     spin_loop();
-        // }
-        // queue.pop_used().unwrap();
-        // self.rx_buffer.sync(0..8).unwrap();
-        // let status = self.rx_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        // let latency_bytes = self.rx_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        // early_println!(
-        //     "Response of IO Message: status={:?}, latency_bytes={:?}", status, latency_bytes
-        // );
-
-
-        // --------------------------------------------------------------------------------------
-
-
-        early_println!("Stop PCM");
-        let req = SndPcmHdr {
-            hdr: MessageHdr::PcmStop as u32,
-            stream_id: 0,
-        };
-        self.send(&req, PCM_HDR_SIZE, 8, &mut queue);
-        self.event_buffer.sync(0..8).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let data = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        early_println!(
-            "Response of stopping PCM: hdr={:?}, data={:?}", hdr, data
-        );
-
-        // --------------------------------------------------------------------------------------
-
-        early_println!("Release PCM");
-        let req = SndPcmHdr {
-            hdr: MessageHdr::PcmRelease as u32,
-            stream_id: 0,
-        };
-        self.send(&req, PCM_HDR_SIZE, 8, &mut queue);
-        self.event_buffer.sync(0..8).unwrap();
-        let hdr = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        let data = self.event_buffer.reader().unwrap().read_once::<u32>().unwrap();
-        early_println!(
-            "Response of releasing PCM: hdr={:?}, data={:?}", hdr, data
-        );
-
-    }
-
-
-    pub fn send<T: Pod>(&self, data: &T, send_size: usize, recv_size: usize, queue: &mut SpinLockGuard<VirtQueue, LocalIrqDisabled>) {
-        let ctl_slice = {
-            let req_slice =
-                DmaStreamSlice::new(self.ctl_buffer.clone(), 0, send_size);
-            req_slice.write_val(0, data).unwrap();
-            req_slice.sync().unwrap();
-            req_slice
-        };
-
-        let event_slice = {
-            let resp_slice =
-                DmaStreamSlice::new(self.event_buffer.clone(), 0, recv_size);
-            resp_slice
-        };
-
-        queue
-            .add_dma_buf(&[&ctl_slice], &[&event_slice])
-            .expect("add queue failed");
-        if queue.should_notify() {
-            queue.notify();
-        }
-
-        while !queue.can_pop() {
-            spin_loop();
-        }
-
-        queue.pop_used().unwrap();
-    }
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod)]
-pub struct SndPcmQueryInfo {
-    pub hdr: u32,    // 通用信息头
-    pub start_id: u32,        // 小端：起始 ID
-    pub count: u32,           // 小端：查询的条目数量
-    pub size: u32,            // 小端：每个条目的大小
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod)]
-pub struct SndPcmInfo {
-    pub hdr: u32,   // 嵌套的通用信息头
-    pub features: u32,        // 小端：特性位掩码 (1 << VIRTIO_SND_PCM_F_XXX)
-    pub formats: u64,         // 小端：支持的采样格式 (1 << VIRTIO_SND_PCM_FMT_XXX)
-    pub rates: u64,           // 小端：支持的采样率 (1 << VIRTIO_SND_PCM_RATE_XXX)
-    pub direction: u8,        // 数据流方向 (VIRTIO_SND_D_XXX)
-    pub channels_min: u8,     // 支持的最小通道数
-    pub channels_max: u8,     // 支持的最大通道数
-    pub padding: [u8; 5],     // 填充字节
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod)]
-pub struct SndPcmSetParams {
-    pub hdr: u32,           // 头部，表示结构体的类型或标识符 (VIRTIO_SND_R_PCM_SET_PARAMS)
-    pub buffer_bytes: u32,  // 缓冲区大小，单位字节
-    pub period_bytes: u32,  // 每个周期的字节数
-    pub features: u32,      // 特性标志位掩码 (1 << VIRTIO_SND_PCM_F_XXX)
-    pub channels: u8,       // 音频通道数
-    pub format: u8,         // 音频格式 (VIRTIO_SND_PCM_FMT_XXX)
-    pub rate: u8,           // 采样率 (VIRTIO_SND_PCM_RATE_XXX)
-    pub padding: [u8; 5],   // 填充字节，用于对齐
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod)]
-pub struct SndPcmHdr {
-    pub hdr: u32, // 通用信息头
-    pub stream_id: u32,    // 小端：PCM 流 ID
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, Pod)]
-pub struct SndPcmIOMessage {
-    pub stream_id: u32,   
-    pub buffer: u8,//[u16; 5],
-}
-
-const PCM_HDR_SIZE: usize = size_of::<SndPcmHdr>();
-const PCM_SET_PARAMS_SIZE: usize = size_of::<SndPcmSetParams>();
-const PCM_INFO_QUERY_SIZE: usize = size_of::<SndPcmQueryInfo>();
-const PCM_INFO_SIZE: usize = size_of::<SndPcmInfo>();
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use log::debug;
+use ostd::{Pod};
+use ostd::mm::{DmaDirection, DmaStream, DmaStreamSlice, FrameAllocOptions, Infallible, VmIo, VmReader};
+use ostd::sync::{LocalIrqDisabled, SpinLock, WaitQueue};
+use ostd::task::TaskOptions;
+use ostd::trap::TrapFrame;
+use crate::device::sound::config::{MessageHdr, SoundFeatures, VirtioSoundConfig};
+use crate::device::sound::ctl::{
+    SndCtlEnumItemRaw, SndCtlEnumItemsQuery, SndCtlHdr, SndCtlInfo, SndCtlInfoRaw,
+    SndCtlQueryInfo, SndCtlTlvHdr, SndCtlValue, SndCtlWriteRequest, CTL_ENUM_ITEMS_QUERY_SIZE,
+    CTL_ENUM_ITEM_SIZE, CTL_HDR_SIZE, CTL_INFO_SIZE, CTL_MAX_CHANNELS, CTL_QUERY_INFO_SIZE,
+    CTL_TLV_HDR_SIZE, CTL_VALUE_SIZE, CTL_WRITE_REQUEST_SIZE,
+};
+use crate::device::sound::jack::{
+    JackCallback, SndJackHdr, SndJackInfo, SndJackInfoRaw, SndJackQueryInfo, SndJackRemap,
+    JACK_HDR_SIZE, JACK_INFO_SIZE, JACK_QUERY_INFO_SIZE, JACK_REMAP_SIZE,
+};
+use crate::device::sound::stream::{Stream, StreamDirection};
+use crate::device::VirtioDeviceError;
+use crate::queue::VirtQueue;
+use crate::transport::{ConfigManager, VirtioTransport};
+
+/// Number of bytes backing a single DMA frame, matching the segment granularity
+/// used by `FrameAllocOptions`.
+const FRAME_SIZE: usize = 4096;
+
+/// Capacity of the event-queue notification buffer: comfortably larger than the
+/// events `dispatch_event` decodes (a `u32` header followed by a `u32` jack or
+/// stream id).
+const EVENT_NOTIFY_SIZE: usize = 64;
+
+/// Capacity of the TLV scratch buffers (dB scale tables and similar small blobs).
+const CTL_TLV_BUFFER_SIZE: usize = 4 * FRAME_SIZE;
+
+/// Size of the bare `virtio_snd_hdr` status word (a single `le32`) every
+/// control-queue reply is prefixed with.
+const STATUS_HDR_SIZE: usize = 4;
+
+pub type SoundCallback = dyn Fn(VmReader<Infallible>) + Send + Sync;
+
+pub struct SoundDevice {
+    config_manager: ConfigManager<VirtioSoundConfig>,
+    transport: SpinLock<Box<dyn VirtioTransport>>,
+    queue: SpinLock<VirtQueue>,
+    txq: SpinLock<VirtQueue>,
+    rxq: SpinLock<VirtQueue>,
+    eventq: SpinLock<VirtQueue>,
+    tx_header_buffer: DmaStream,
+    tx_status_buffer: DmaStream,
+    rx_header_buffer: DmaStream,
+    rx_status_buffer: DmaStream,
+    ctl_buffer: DmaStream,
+    event_buffer: DmaStream,
+    event_notify_buffer: DmaStream,
+    ctl_tlv_write_buffer: DmaStream,
+    ctl_tlv_read_buffer: DmaStream,
+    streams: SpinLock<BTreeMap<u32, StreamRuntime>, LocalIrqDisabled>,
+    jacks: SpinLock<BTreeMap<u32, SndJackInfo>, LocalIrqDisabled>,
+    jack_callback: SpinLock<Option<Box<JackCallback>>, LocalIrqDisabled>,
+    /// Whether the device offered, and we negotiated, `VIRTIO_SND_F_CTLS`.
+    /// The control-element subsystem is a no-op when this is `false`.
+    ctls_enabled: bool,
+    controls: SpinLock<BTreeMap<u32, SndCtlInfo>, LocalIrqDisabled>,
+    ctl_waiter: QueueWaiter,
+    tx_waiter: QueueWaiter,
+    rx_waiter: QueueWaiter,
+    /// `PcmPeriodElapsed`/`PcmXrun` notifications queued by the event IRQ handler
+    /// for `run_period_worker` to process outside of interrupt context.
+    pending_period_events: SpinLock<VecDeque<PeriodEvent>, LocalIrqDisabled>,
+    worker_wait_queue: WaitQueue,
+    stream_callbacks: SpinLock<BTreeMap<u32, Arc<SoundCallback>>, LocalIrqDisabled>,
+    // callbacks: RwLock<Vec<&'static SoundCallback>, LocalIrqDisabled>,
+}
+
+/// Per-queue completion tracking: lets a submitter block instead of busy-spinning
+/// until the IRQ handler observes its specific descriptor chain (keyed by the
+/// `head` index `add_dma_buf` returns) come back through `pop_used`.
+struct QueueWaiter {
+    wait_queue: WaitQueue,
+    done: SpinLock<BTreeMap<u16, u32>, LocalIrqDisabled>,
+}
+
+impl QueueWaiter {
+    fn new() -> Self {
+        Self {
+            wait_queue: WaitQueue::new(),
+            done: SpinLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Blocks the caller until `head`'s completion is posted, returning the used length.
+    fn wait_for(&self, head: u16) -> u32 {
+        self.wait_queue.wait_until(|| self.done.lock().remove(&head))
+    }
+
+    /// Called from IRQ context: records that `head` completed with `len` bytes used
+    /// and wakes whichever submitter is waiting on it.
+    fn complete(&self, head: u16, len: u32) {
+        self.done.lock().insert(head, len);
+        self.wait_queue.wake_all();
+    }
+}
+
+/// Drains every completed descriptor chain currently on `queue` and forwards
+/// each `(head, len)` to `waiter`.
+fn drain_queue(queue: &SpinLock<VirtQueue>, waiter: &QueueWaiter) {
+    let mut queue = queue.disable_irq().lock();
+    while queue.can_pop() {
+        let (head, len) = queue.pop_used().unwrap();
+        waiter.complete(head, len);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PeriodEvent {
+    stream_id: u32,
+    xrun: bool,
+}
+
+/// One period's worth of DMA storage, scattered across independently-allocated
+/// single-frame segments rather than one `buffer_bytes`-sized contiguous region.
+/// Mirrors ALSA's SG-buffer PCM allocator: a large period can be satisfied even
+/// when the allocator has no single contiguous run of `period_bytes` free, at
+/// the cost of submitting one virtqueue descriptor per segment instead of one.
+struct Period {
+    segments: Vec<DmaStream>,
+}
+
+/// Per-stream negotiated state: the period-sized DMA buffers the stream is
+/// currently reading from / writing into, and which one is next in line.
+///
+/// `params` and `running` are not needed for normal IO, but stay cached here
+/// so `SoundDevice::suspend`/`resume` can replay `SetParams` -> `Prepare` (and
+/// `Start`, if the stream was running) without the guest re-opening the stream.
+struct StreamRuntime {
+    direction: StreamDirection,
+    period_bytes: u32,
+    periods: Vec<Period>,
+    next_period: usize,
+    params: SndPcmSetParams,
+    running: bool,
+}
+
+impl StreamRuntime {
+    fn new(direction: StreamDirection, params: SndPcmSetParams) -> Self {
+        let buffer_bytes = params.buffer_bytes;
+        let period_bytes = params.period_bytes.max(1);
+        let num_periods = (buffer_bytes.max(period_bytes) / period_bytes).max(1) as usize;
+        let dma_direction = match direction {
+            StreamDirection::Output => DmaDirection::ToDevice,
+            StreamDirection::Input => DmaDirection::FromDevice,
+        };
+        let segments_per_period = (period_bytes as usize).div_ceil(FRAME_SIZE).max(1);
+        let periods = (0..num_periods)
+            .map(|_| Period {
+                segments: (0..segments_per_period)
+                    .map(|_| {
+                        let segment = FrameAllocOptions::new().alloc_segment(1).unwrap();
+                        DmaStream::map(segment.into(), dma_direction, false).unwrap()
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            direction,
+            period_bytes,
+            periods,
+            next_period: 0,
+            params,
+            running: false,
+        }
+    }
+
+    fn next_free_period(&self) -> &Period {
+        &self.periods[self.next_period]
+    }
+
+    fn advance(&mut self) {
+        self.next_period = (self.next_period + 1) % self.periods.len();
+    }
+}
+
+/// Splits `len` bytes across `segments`' single-frame slices, writing `pcm`
+/// into each (for TX) and returning the resulting descriptor-sized slices.
+fn write_period_slices(
+    segments: &[DmaStream],
+    pcm: &[u8],
+    len: usize,
+) -> Vec<DmaStreamSlice<DmaStream>> {
+    let mut slices = Vec::with_capacity(segments.len());
+    let mut offset = 0;
+    for segment in segments {
+        if offset >= len {
+            break;
+        }
+        let seg_len = (len - offset).min(FRAME_SIZE);
+        let slice = DmaStreamSlice::new(segment.clone(), 0, seg_len);
+        slice.write_bytes(0, &pcm[offset..offset + seg_len]).unwrap();
+        slice.sync().unwrap();
+        slices.push(slice);
+        offset += seg_len;
+    }
+    slices
+}
+
+/// Builds device-writable slices across `segments`' single-frame chunks that
+/// together can hold up to `len` bytes (for RX), paired with each slice's length.
+fn read_period_slices(segments: &[DmaStream], len: usize) -> Vec<(DmaStreamSlice<DmaStream>, usize)> {
+    let mut slices = Vec::with_capacity(segments.len());
+    let mut offset = 0;
+    for segment in segments {
+        if offset >= len {
+            break;
+        }
+        let seg_len = (len - offset).min(FRAME_SIZE);
+        slices.push((DmaStreamSlice::new(segment.clone(), 0, seg_len), seg_len));
+        offset += seg_len;
+    }
+    slices
+}
+
+impl SoundDevice {
+    pub(crate) fn negotiate_features(features: u64) -> u64 {
+        // Accept every bit the device offered: transport/ring feature bits
+        // (e.g. `VIRTIO_F_VERSION_1`) must pass through untouched, and
+        // `VIRTIO_SND_F_CTLS` rides along automatically since it's already
+        // part of `features` whenever the device offers it.
+        features
+    }
+
+    pub fn init(mut transport: Box<dyn VirtioTransport>) -> Result<(), VirtioDeviceError> {
+        let config_manager = VirtioSoundConfig::new_manager(transport.as_ref());
+        let config = config_manager.read_config();
+        debug!("virtio_sound_config = {:?}", config);
+
+        debug!("begin initializing virtqueues");
+        // Per the virtio-sound spec: controlq=0, eventq=1, txq=2, rxq=3.
+        const Q_INDEX: u16 = 0;
+        const EVENTQ_INDEX: u16 = 1;
+        const TXQ_INDEX: u16 = 2;
+        const RXQ_INDEX: u16 = 3;
+
+        let message_queue = SpinLock::new(VirtQueue::new(Q_INDEX, 2, transport.as_mut()).unwrap());
+        let eventq = SpinLock::new(VirtQueue::new(EVENTQ_INDEX, 2, transport.as_mut()).unwrap());
+        let txq = SpinLock::new(VirtQueue::new(TXQ_INDEX, 2, transport.as_mut()).unwrap());
+        let rxq = SpinLock::new(VirtQueue::new(RXQ_INDEX, 2, transport.as_mut()).unwrap());
+
+        let tx_header_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(1).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::ToDevice, false).unwrap()
+        };
+
+        let tx_status_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(1).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
+        };
+
+        let rx_header_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(1).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::ToDevice, false).unwrap()
+        };
+
+        let rx_status_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(1).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
+        };
+
+        let ctl_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(100).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::ToDevice, false).unwrap()
+        };
+
+        let event_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(100).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
+        };
+
+        let event_notify_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(1).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
+        };
+
+        let ctl_tlv_write_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(4).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::ToDevice, false).unwrap()
+        };
+
+        let ctl_tlv_read_buffer = {
+            let vm_segment = FrameAllocOptions::new().alloc_segment(4).unwrap();
+            DmaStream::map(vm_segment.into(), DmaDirection::FromDevice, false).unwrap()
+        };
+
+        // VIRTIO_SND_F_CTLS is the only device-specific feature bit defined today,
+        // so whatever `negotiate_features` accepted tells us whether it was negotiated.
+        let ctls_enabled = SoundFeatures::from_bits_truncate(transport.read_device_features())
+            .contains(SoundFeatures::VIRTIO_SND_F_CTLS)
+            && config.controls > 0;
+        if !ctls_enabled {
+            debug!("VIRTIO_SND_F_CTLS not negotiated or no controls exposed, skipping control-element subsystem");
+        }
+
+        let device = Arc::new(
+            Self {
+                config_manager,
+                transport: SpinLock::new(transport),
+                queue: message_queue,
+                txq,
+                rxq,
+                eventq,
+                tx_header_buffer,
+                tx_status_buffer,
+                rx_header_buffer,
+                rx_status_buffer,
+                ctl_buffer,
+                event_buffer,
+                event_notify_buffer,
+                ctl_tlv_write_buffer,
+                ctl_tlv_read_buffer,
+                streams: SpinLock::new(BTreeMap::new()),
+                jacks: SpinLock::new(BTreeMap::new()),
+                jack_callback: SpinLock::new(None),
+                ctls_enabled,
+                controls: SpinLock::new(BTreeMap::new()),
+                ctl_waiter: QueueWaiter::new(),
+                tx_waiter: QueueWaiter::new(),
+                rx_waiter: QueueWaiter::new(),
+                pending_period_events: SpinLock::new(VecDeque::new()),
+                worker_wait_queue: WaitQueue::new(),
+                stream_callbacks: SpinLock::new(BTreeMap::new()),
+                // callbacks: RwLock::new(Vec::new()),
+            });
+
+
+        // Register irq callbacks
+        let mut transport = device.transport.disable_irq().lock();
+
+        fn config_space_change(_: &TrapFrame) {
+            debug!("sound device config space change");
+        }
+
+        transport
+            .register_cfg_callback(Box::new(config_space_change))
+            .unwrap();
+
+        let ctl_irq_device = device.clone();
+        transport
+            .register_queue_callback(
+                Q_INDEX,
+                Box::new(move |_: &TrapFrame| ctl_irq_device.handle_ctl_irq()),
+                false,
+            )
+            .unwrap();
+
+        let tx_irq_device = device.clone();
+        transport
+            .register_queue_callback(
+                TXQ_INDEX,
+                Box::new(move |_: &TrapFrame| tx_irq_device.handle_tx_irq()),
+                false,
+            )
+            .unwrap();
+
+        let rx_irq_device = device.clone();
+        transport
+            .register_queue_callback(
+                RXQ_INDEX,
+                Box::new(move |_: &TrapFrame| rx_irq_device.handle_rx_irq()),
+                false,
+            )
+            .unwrap();
+
+        let event_irq_device = device.clone();
+        transport
+            .register_queue_callback(
+                EVENTQ_INDEX,
+                Box::new(move |_: &TrapFrame| event_irq_device.handle_event_irq()),
+                false,
+            )
+            .unwrap();
+
+        transport.finish_init();
+        drop(transport);
+
+        // Give the device somewhere to write the first unsolicited event
+        // (jack/PCM period notifications) before any are generated.
+        device.post_event_buffer();
+
+        // Run period-elapsed/xrun handling on a dedicated kernel task so it
+        // never has to do non-atomic work (buffer refills, callbacks) from
+        // `handle_event_irq`'s hard-IRQ context.
+        let worker_device = device.clone();
+        TaskOptions::new(move || worker_device.run_period_worker())
+            .spawn()
+            .expect("failed to spawn virtio-sound period worker");
+
+        Ok(())
+    }
+
+    pub fn handle_event_irq(&self) {
+        debug!("handling event irq");
+        while self.eventq.disable_irq().lock().can_pop() {
+            self.eventq.disable_irq().lock().pop_used().unwrap();
+            self.dispatch_event();
+            self.post_event_buffer();
+        }
+    }
+
+    /// Decodes the pending notification in `event_notify_buffer` and routes it:
+    /// jack hot-plug events update the cached [`SndJackInfo`] and fire the
+    /// registered [`JackCallback`]; `PcmPeriodElapsed`/`PcmXrun` are queued for
+    /// `run_period_worker`; anything else is logged and dropped.
+    fn dispatch_event(&self) {
+        self.event_notify_buffer.sync(0..EVENT_NOTIFY_SIZE).unwrap();
+        let mut reader = self.event_notify_buffer.reader().unwrap();
+        let hdr = reader.read_once::<u32>().unwrap();
+
+        if hdr == MessageHdr::JackConnected as u32 || hdr == MessageHdr::JackDisconnected as u32 {
+            let jack_id = reader.read_once::<u32>().unwrap();
+            let connected = hdr == MessageHdr::JackConnected as u32;
+            if let Some(info) = self.jacks.lock().get_mut(&jack_id) {
+                info.connected = connected;
+            }
+            if let Some(callback) = self.jack_callback.lock().as_ref() {
+                callback(jack_id, connected);
+            }
+            debug!("jack {jack_id} {}", if connected { "connected" } else { "disconnected" });
+        } else if hdr == MessageHdr::PcmPeriodElapsed as u32 || hdr == MessageHdr::PcmXrun as u32 {
+            // Don't refill buffers or invoke the stream callback here: queue the
+            // notification and let `run_period_worker` do that non-atomic work.
+            let stream_id = reader.read_once::<u32>().unwrap();
+            let xrun = hdr == MessageHdr::PcmXrun as u32;
+            self.pending_period_events
+                .lock()
+                .push_back(PeriodEvent { stream_id, xrun });
+            self.worker_wait_queue.wake_all();
+        } else {
+            debug!("unhandled event: hdr={:?}", hdr);
+        }
+    }
+
+    /// (Re-)submits the single notification buffer as a device-writable descriptor
+    /// on the event queue, so the device always has somewhere to report the next event.
+    fn post_event_buffer(&self) {
+        let notify_slice = DmaStreamSlice::new(self.event_notify_buffer.clone(), 0, EVENT_NOTIFY_SIZE);
+        let mut eventq = self.eventq.disable_irq().lock();
+        eventq
+            .add_dma_buf(&[], &[&notify_slice])
+            .expect("add queue failed");
+        if eventq.should_notify() {
+            eventq.notify();
+        }
+    }
+
+    fn handle_ctl_irq(&self) {
+        drain_queue(&self.queue, &self.ctl_waiter);
+    }
+
+    fn handle_tx_irq(&self) {
+        drain_queue(&self.txq, &self.tx_waiter);
+    }
+
+    fn handle_rx_irq(&self) {
+        drain_queue(&self.rxq, &self.rx_waiter);
+    }
+
+    /// Drains queued `PcmPeriodElapsed`/`PcmXrun` notifications and, for each
+    /// elapsed period, hands the period at the stream's current ring position
+    /// to its registered [`SoundCallback`] and re-submits it to the device; the
+    /// ring cursor itself is left unchanged. Xrun notifications are only logged.
+    /// Runs as a dedicated worker so period handling stays out of the hard IRQ path.
+    pub fn run_period_worker(self: &Arc<Self>) -> ! {
+        loop {
+            let event = self
+                .worker_wait_queue
+                .wait_until(|| self.pending_period_events.lock().pop_front());
+            self.handle_period_event(event);
+        }
+    }
+
+    fn handle_period_event(&self, event: PeriodEvent) {
+        if event.xrun {
+            debug!("stream {} underrun/overrun (xrun)", event.stream_id);
+            return;
+        }
+
+        // `next_period` is advanced solely by `pcm_write_period`/`pcm_read_period`
+        // (the explicit per-stream IO calls); this only reads the ring's current
+        // position so resubmission below doesn't race the synchronous IO path
+        // onto the same cursor.
+        let (segments, direction, period_bytes) = {
+            let streams = self.streams.lock();
+            let Some(runtime) = streams.get(&event.stream_id) else {
+                return;
+            };
+            let segments = runtime.next_free_period().segments.clone();
+            (segments, runtime.direction, runtime.period_bytes as usize)
+        };
+
+        if let Some(callback) = self.stream_callbacks.lock().get(&event.stream_id) {
+            // The period is scattered across independent segments; hand each
+            // one to the callback in order rather than inventing a combined reader.
+            for segment in &segments {
+                callback(segment.reader().unwrap());
+            }
+        }
+
+        // Re-submit the period so the device always has the next descriptor
+        // queued, rather than stalling after a single notification.
+        match direction {
+            StreamDirection::Output => {
+                self.resubmit_tx_segments(event.stream_id, &segments, period_bytes)
+            }
+            StreamDirection::Input => {
+                self.resubmit_rx_segments(event.stream_id, &segments, period_bytes)
+            }
+        }
+    }
+
+    /// Re-submits `segments` as a fresh TX descriptor chain carrying whatever
+    /// playback data they already hold (the period-elapsed path has no new
+    /// data to write), keeping the stream fed until the guest calls
+    /// `write_period` again.
+    fn resubmit_tx_segments(&self, stream_id: u32, segments: &[DmaStream], period_bytes: usize) {
+        let header_slice = DmaStreamSlice::new(self.tx_header_buffer.clone(), 0, PCM_IO_HEADER_SIZE);
+        header_slice.write_val(0, &SndPcmIOHeader { stream_id }).unwrap();
+        header_slice.sync().unwrap();
+
+        let payload_slices = read_period_slices(segments, period_bytes);
+        let status_slice = DmaStreamSlice::new(self.tx_status_buffer.clone(), 0, PCM_IO_STATUS_SIZE);
+
+        let mut readable: Vec<&DmaStreamSlice<DmaStream>> =
+            Vec::with_capacity(1 + payload_slices.len());
+        readable.push(&header_slice);
+        readable.extend(payload_slices.iter().map(|(slice, _)| slice));
+
+        let head = {
+            let mut txq = self.txq.disable_irq().lock();
+            let head = txq
+                .add_dma_buf(&readable, &[&status_slice])
+                .expect("add queue failed");
+            if txq.should_notify() {
+                txq.notify();
+            }
+            head
+        };
+        self.tx_waiter.wait_for(head);
+    }
+
+    /// Re-submits `segments` as a fresh RX descriptor chain so the device has
+    /// somewhere to write the next period's captured data.
+    fn resubmit_rx_segments(&self, stream_id: u32, segments: &[DmaStream], period_bytes: usize) {
+        let header_slice = DmaStreamSlice::new(self.rx_header_buffer.clone(), 0, PCM_IO_HEADER_SIZE);
+        header_slice.write_val(0, &SndPcmIOHeader { stream_id }).unwrap();
+        header_slice.sync().unwrap();
+
+        let payload_slices = read_period_slices(segments, period_bytes);
+        let status_slice = DmaStreamSlice::new(self.rx_status_buffer.clone(), 0, PCM_IO_STATUS_SIZE);
+
+        let mut writable: Vec<&DmaStreamSlice<DmaStream>> =
+            Vec::with_capacity(1 + payload_slices.len());
+        writable.extend(payload_slices.iter().map(|(slice, _)| slice));
+        writable.push(&status_slice);
+
+        let head = {
+            let mut rxq = self.rxq.disable_irq().lock();
+            let head = rxq
+                .add_dma_buf(&[&header_slice], &writable)
+                .expect("add queue failed");
+            if rxq.should_notify() {
+                rxq.notify();
+            }
+            head
+        };
+        self.rx_waiter.wait_for(head);
+    }
+
+    /// Registers the callback invoked whenever `stream_id` reports a period elapsed.
+    pub fn register_stream_callback(&self, stream_id: u32, callback: Arc<SoundCallback>) {
+        self.stream_callbacks.lock().insert(stream_id, callback);
+    }
+
+    /// Issues `JACK_INFO` for every jack reported in `config.jacks` and caches the result.
+    pub fn query_jacks(&self) -> Vec<SndJackInfo> {
+        let jack_count = self.config_manager.read_config().jacks;
+        let req = SndJackQueryInfo {
+            hdr: MessageHdr::JackInfo as u32,
+            start_id: 0,
+            count: jack_count,
+            size: JACK_INFO_SIZE as u32,
+        };
+        let recv_size = STATUS_HDR_SIZE + jack_count as usize * JACK_INFO_SIZE;
+
+        self.send(&req, JACK_QUERY_INFO_SIZE, recv_size);
+
+        self.event_buffer.sync(0..recv_size).unwrap();
+        let mut reader = self.event_buffer.reader().unwrap();
+        reader.read_once::<u32>().unwrap(); // skip the leading status hdr
+        let mut jacks = Vec::with_capacity(jack_count as usize);
+        let mut cache = self.jacks.lock();
+        for jack_id in 0..jack_count {
+            let raw = reader.read_once::<SndJackInfoRaw>().unwrap();
+            let info = SndJackInfo {
+                jack_id,
+                features: raw.features,
+                hda_fn_nid: raw.hda_fn_nid,
+                connected: raw.connected != 0,
+            };
+            cache.insert(jack_id, info);
+            jacks.push(info);
+        }
+        jacks
+    }
+
+    /// Remaps `jack_id` to a new pin-complex association/sequence via `JACK_REMAP`.
+    pub fn remap_jack(&self, jack_id: u32, association: u32, sequence: u32) {
+        let req = SndJackRemap {
+            hdr: SndJackHdr {
+                hdr: MessageHdr::JackRemap as u32,
+                jack_id,
+            },
+            association,
+            sequence,
+        };
+        self.send(&req, JACK_REMAP_SIZE, JACK_HDR_SIZE);
+    }
+
+    /// Registers the callback invoked on every `JackConnected`/`JackDisconnected` event.
+    pub fn register_jack_callback(&self, callback: Box<JackCallback>) {
+        *self.jack_callback.lock() = Some(callback);
+    }
+
+    /// Issues `CTL_INFO` for every control element in `config.controls` and caches the result.
+    /// A no-op returning an empty `Vec` if `VIRTIO_SND_F_CTLS` was not negotiated.
+    pub fn query_controls(&self) -> Vec<SndCtlInfo> {
+        if !self.ctls_enabled {
+            return Vec::new();
+        }
+
+        let control_count = self.config_manager.read_config().controls;
+        let req = SndCtlQueryInfo {
+            hdr: MessageHdr::CtlInfo as u32,
+            start_id: 0,
+            count: control_count,
+            size: CTL_INFO_SIZE as u32,
+        };
+        let recv_size = STATUS_HDR_SIZE + control_count as usize * CTL_INFO_SIZE;
+
+        self.send(&req, CTL_QUERY_INFO_SIZE, recv_size);
+
+        self.event_buffer.sync(0..recv_size).unwrap();
+        let mut reader = self.event_buffer.reader().unwrap();
+        reader.read_once::<u32>().unwrap(); // skip the leading status hdr
+        let mut controls = Vec::with_capacity(control_count as usize);
+        let mut cache = self.controls.lock();
+        for _ in 0..control_count {
+            let raw = reader.read_once::<SndCtlInfoRaw>().unwrap();
+            let info = SndCtlInfo {
+                control_id: raw.control_id,
+                item_type: raw.item_type,
+                access: raw.access,
+                count: raw.count,
+                min: raw.min,
+                max: raw.max,
+                step: raw.step,
+                name: parse_c_str(&raw.name),
+            };
+            cache.insert(info.control_id, info.clone());
+            controls.push(info);
+        }
+        controls
+    }
+
+    /// Fetches the names of the first `count` enumerated items of `control_id` via `CTL_ENUM_ITEMS`.
+    pub fn ctl_enum_items(&self, control_id: u32, count: u32) -> Vec<String> {
+        if !self.ctls_enabled {
+            return Vec::new();
+        }
+
+        let mut items = Vec::with_capacity(count as usize);
+        for item_id in 0..count {
+            let req = SndCtlEnumItemsQuery {
+                hdr: SndCtlHdr {
+                    hdr: MessageHdr::CtlEnumItems as u32,
+                    control_id,
+                },
+                item_id,
+            };
+            let recv_size = STATUS_HDR_SIZE + CTL_ENUM_ITEM_SIZE;
+            self.send(&req, CTL_ENUM_ITEMS_QUERY_SIZE, recv_size);
+
+            self.event_buffer.sync(0..recv_size).unwrap();
+            let mut reader = self.event_buffer.reader().unwrap();
+            reader.read_once::<u32>().unwrap(); // skip the leading status hdr
+            let raw = reader.read_once::<SndCtlEnumItemRaw>().unwrap();
+            items.push(parse_c_str(&raw.name));
+        }
+        items
+    }
+
+    /// Reads the current scalar value(s) of `control_id` via `CTL_READ`.
+    /// A no-op returning all zeroes if `VIRTIO_SND_F_CTLS` was not negotiated.
+    pub fn ctl_read(&self, control_id: u32) -> [i32; CTL_MAX_CHANNELS] {
+        if !self.ctls_enabled {
+            return [0; CTL_MAX_CHANNELS];
+        }
+
+        let req = SndCtlHdr {
+            hdr: MessageHdr::CtlRead as u32,
+            control_id,
+        };
+        let recv_size = STATUS_HDR_SIZE + CTL_VALUE_SIZE;
+        self.send(&req, CTL_HDR_SIZE, recv_size);
+
+        self.event_buffer.sync(0..recv_size).unwrap();
+        let mut reader = self.event_buffer.reader().unwrap();
+        reader.read_once::<u32>().unwrap(); // skip the leading status hdr
+        reader.read_once::<SndCtlValue>().unwrap().value
+    }
+
+    /// Writes the scalar value(s) of `control_id` via `CTL_WRITE`.
+    pub fn ctl_write(&self, control_id: u32, value: [i32; CTL_MAX_CHANNELS]) {
+        if !self.ctls_enabled {
+            return;
+        }
+
+        let req = SndCtlWriteRequest {
+            hdr: SndCtlHdr {
+                hdr: MessageHdr::CtlWrite as u32,
+                control_id,
+            },
+            value: SndCtlValue { value },
+        };
+        self.send(&req, CTL_WRITE_REQUEST_SIZE, 8);
+    }
+
+    /// Reads up to `buf.len()` bytes of TLV data for `control_id` via `CTL_TLV_READ`
+    /// (e.g. a dB scale) and returns the number of bytes actually filled.
+    pub fn ctl_tlv_read(&self, control_id: u32, buf: &mut [u8]) -> usize {
+        if !self.ctls_enabled {
+            return 0;
+        }
+
+        let len = buf.len().min(CTL_TLV_BUFFER_SIZE);
+        let req = SndCtlTlvHdr {
+            hdr: SndCtlHdr {
+                hdr: MessageHdr::CtlTlvRead as u32,
+                control_id,
+            },
+            size: len as u32,
+        };
+
+        let payload_slice = DmaStreamSlice::new(self.ctl_tlv_read_buffer.clone(), 0, len);
+        let header_slice = DmaStreamSlice::new(self.ctl_buffer.clone(), 0, CTL_TLV_HDR_SIZE);
+        header_slice.write_val(0, &req).unwrap();
+        header_slice.sync().unwrap();
+
+        let head = {
+            let mut queue = self.queue.disable_irq().lock();
+            let head = queue
+                .add_dma_buf(&[&header_slice], &[&payload_slice])
+                .expect("add queue failed");
+            if queue.should_notify() {
+                queue.notify();
+            }
+            head
+        };
+        self.ctl_waiter.wait_for(head);
+
+        payload_slice.sync().unwrap();
+        payload_slice.read_bytes(0, &mut buf[..len]).unwrap();
+        len
+    }
+
+    /// Writes `data` as the TLV payload for `control_id` via `CTL_TLV_WRITE`.
+    pub fn ctl_tlv_write(&self, control_id: u32, data: &[u8]) {
+        if !self.ctls_enabled {
+            return;
+        }
+
+        let len = data.len().min(CTL_TLV_BUFFER_SIZE);
+        let req = SndCtlTlvHdr {
+            hdr: SndCtlHdr {
+                hdr: MessageHdr::CtlTlvWrite as u32,
+                control_id,
+            },
+            size: len as u32,
+        };
+
+        let header_slice = DmaStreamSlice::new(self.ctl_buffer.clone(), 0, CTL_TLV_HDR_SIZE);
+        header_slice.write_val(0, &req).unwrap();
+        header_slice.sync().unwrap();
+
+        let payload_slice = DmaStreamSlice::new(self.ctl_tlv_write_buffer.clone(), 0, len);
+        payload_slice.write_bytes(0, &data[..len]).unwrap();
+        payload_slice.sync().unwrap();
+
+        let status_slice = DmaStreamSlice::new(self.event_buffer.clone(), 0, 8);
+
+        let head = {
+            let mut queue = self.queue.disable_irq().lock();
+            let head = queue
+                .add_dma_buf(&[&header_slice, &payload_slice], &[&status_slice])
+                .expect("add queue failed");
+            if queue.should_notify() {
+                queue.notify();
+            }
+            head
+        };
+        self.ctl_waiter.wait_for(head);
+    }
+
+    /// Issues a `CTL_TLV_COMMAND` (e.g. apply/reset) for `control_id`.
+    pub fn ctl_tlv_command(&self, control_id: u32) {
+        if !self.ctls_enabled {
+            return;
+        }
+
+        let req = SndCtlHdr {
+            hdr: MessageHdr::CtlTlvCommand as u32,
+            control_id,
+        };
+        self.send(&req, CTL_HDR_SIZE, 8);
+    }
+
+    /// Opens a PCM stream for IO. `stream_id` must be one of `config.streams` entries
+    /// reported by the device; `direction` is the direction it was enumerated with.
+    pub fn open_stream(self: &Arc<Self>, stream_id: u32, direction: StreamDirection) -> Stream {
+        Stream::new(self.clone(), stream_id, direction)
+    }
+
+    /// Sends `PCM_SET_PARAMS` for `stream_id` and (re)allocates its period buffer ring
+    /// to match the negotiated `buffer_bytes`/`period_bytes`.
+    pub(crate) fn pcm_set_params(
+        &self,
+        stream_id: u32,
+        direction: StreamDirection,
+        mut params: SndPcmSetParams,
+    ) {
+        params.hdr = MessageHdr::PcmSetParams as u32;
+        params.stream_id = stream_id;
+
+        self.send(&params, PCM_SET_PARAMS_SIZE, 8);
+
+        let runtime = StreamRuntime::new(direction, params);
+        self.streams.lock().insert(stream_id, runtime);
+    }
+
+    /// Sends a bare `{hdr, stream_id}` PCM control message (prepare/start/stop/release)
+    /// and updates the cached `running` state `suspend`/`resume` rely on.
+    pub(crate) fn pcm_control(&self, stream_id: u32, hdr: MessageHdr) {
+        let req = SndPcmHdr {
+            hdr: hdr as u32,
+            stream_id,
+        };
+        self.send(&req, PCM_HDR_SIZE, 8);
+
+        if let Some(runtime) = self.streams.lock().get_mut(&stream_id) {
+            match hdr {
+                MessageHdr::PcmStart => runtime.running = true,
+                MessageHdr::PcmStop | MessageHdr::PcmRelease => runtime.running = false,
+                _ => {}
+            }
+        }
+    }
+
+    /// Quiesces every running stream and the virtqueues ahead of a device suspend.
+    /// Each stream's negotiated parameters and run state stay cached in `streams`
+    /// (the `PcmStop` here is sent directly, not through `pcm_control`, so the
+    /// cached `running` flag keeps recording "was running before suspend" for
+    /// `resume` to read back).
+    pub fn suspend(&self) {
+        let running_streams: Vec<u32> = {
+            let streams = self.streams.lock();
+            streams
+                .iter()
+                .filter(|(_, runtime)| runtime.running)
+                .map(|(&stream_id, _)| stream_id)
+                .collect()
+        };
+
+        for stream_id in running_streams {
+            let req = SndPcmHdr {
+                hdr: MessageHdr::PcmStop as u32,
+                stream_id,
+            };
+            self.send(&req, PCM_HDR_SIZE, 8);
+        }
+
+        drain_queue(&self.txq, &self.tx_waiter);
+        drain_queue(&self.rxq, &self.rx_waiter);
+        drain_queue(&self.queue, &self.ctl_waiter);
+    }
+
+    /// Re-configures every stream that was set up before `suspend`, replaying
+    /// `SetParams` -> `Prepare` from the cached parameters (there is no separate
+    /// resume flag on the wire) and restarting streams that were running.
+    pub fn resume(&self) {
+        let saved: Vec<(u32, StreamDirection, SndPcmSetParams, bool)> = {
+            let streams = self.streams.lock();
+            streams
+                .iter()
+                .map(|(&stream_id, runtime)| {
+                    (stream_id, runtime.direction, runtime.params, runtime.running)
+                })
+                .collect()
+        };
+
+        for (stream_id, direction, params, was_running) in saved {
+            self.pcm_set_params(stream_id, direction, params);
+            self.pcm_control(stream_id, MessageHdr::PcmPrepare);
+            if was_running {
+                self.pcm_control(stream_id, MessageHdr::PcmStart);
+            }
+        }
+    }
+
+    /// Writes one period of playback data on the TX queue and returns `latency_bytes`.
+    /// The period is split across its scatter-gather segments, each submitted as its
+    /// own device-readable descriptor alongside the header and status descriptors.
+    pub(crate) fn pcm_write_period(&self, stream_id: u32, pcm: &[u8]) -> u32 {
+        let (segments, period_bytes) = {
+            let streams = self.streams.lock();
+            let runtime = streams
+                .get(&stream_id)
+                .expect("stream has not been configured with set_params");
+            (runtime.next_free_period().segments.clone(), runtime.period_bytes as usize)
+        };
+
+        let len = pcm.len().min(period_bytes);
+
+        let header_slice = DmaStreamSlice::new(self.tx_header_buffer.clone(), 0, PCM_IO_HEADER_SIZE);
+        header_slice.write_val(0, &SndPcmIOHeader { stream_id }).unwrap();
+        header_slice.sync().unwrap();
+
+        let payload_slices = write_period_slices(&segments, pcm, len);
+        let status_slice = DmaStreamSlice::new(self.tx_status_buffer.clone(), 0, PCM_IO_STATUS_SIZE);
+
+        let mut readable: Vec<&DmaStreamSlice<DmaStream>> =
+            Vec::with_capacity(1 + payload_slices.len());
+        readable.push(&header_slice);
+        readable.extend(payload_slices.iter());
+
+        let head = {
+            let mut txq = self.txq.disable_irq().lock();
+            let head = txq
+                .add_dma_buf(&readable, &[&status_slice])
+                .expect("add queue failed");
+            if txq.should_notify() {
+                txq.notify();
+            }
+            head
+        };
+        self.tx_waiter.wait_for(head);
+
+        let latency_bytes = self.read_io_status(&status_slice);
+        self.streams.lock().get_mut(&stream_id).unwrap().advance();
+        latency_bytes
+    }
+
+    /// Reads one period of captured data from the RX queue into `pcm` and returns `latency_bytes`.
+    /// The period is split across its scatter-gather segments, each submitted as its
+    /// own device-writable descriptor alongside the status descriptor.
+    pub(crate) fn pcm_read_period(&self, stream_id: u32, pcm: &mut [u8]) -> u32 {
+        let (segments, period_bytes) = {
+            let streams = self.streams.lock();
+            let runtime = streams
+                .get(&stream_id)
+                .expect("stream has not been configured with set_params");
+            (runtime.next_free_period().segments.clone(), runtime.period_bytes as usize)
+        };
+
+        let len = pcm.len().min(period_bytes);
+
+        let header_slice = DmaStreamSlice::new(self.rx_header_buffer.clone(), 0, PCM_IO_HEADER_SIZE);
+        header_slice.write_val(0, &SndPcmIOHeader { stream_id }).unwrap();
+        header_slice.sync().unwrap();
+
+        let payload_slices = read_period_slices(&segments, len);
+        let status_slice = DmaStreamSlice::new(self.rx_status_buffer.clone(), 0, PCM_IO_STATUS_SIZE);
+
+        let mut writable: Vec<&DmaStreamSlice<DmaStream>> =
+            Vec::with_capacity(1 + payload_slices.len());
+        writable.extend(payload_slices.iter().map(|(slice, _)| slice));
+        writable.push(&status_slice);
+
+        let head = {
+            let mut rxq = self.rxq.disable_irq().lock();
+            let head = rxq
+                .add_dma_buf(&[&header_slice], &writable)
+                .expect("add queue failed");
+            if rxq.should_notify() {
+                rxq.notify();
+            }
+            head
+        };
+        self.rx_waiter.wait_for(head);
+
+        let mut offset = 0;
+        for (slice, seg_len) in &payload_slices {
+            slice.sync().unwrap();
+            slice.read_bytes(0, &mut pcm[offset..offset + seg_len]).unwrap();
+            offset += seg_len;
+        }
+
+        let latency_bytes = self.read_io_status(&status_slice);
+        self.streams.lock().get_mut(&stream_id).unwrap().advance();
+        latency_bytes
+    }
+
+    fn read_io_status(&self, status_slice: &DmaStreamSlice<DmaStream>) -> u32 {
+        status_slice.sync().unwrap();
+        let mut reader = status_slice.reader().unwrap();
+        let status = reader.read_once::<u32>().unwrap();
+        let latency_bytes = reader.read_once::<u32>().unwrap();
+        debug_assert_eq!(status, MessageHdr::Ok as u32, "IO request failed: status={status}");
+        latency_bytes
+    }
+
+    /// Sends one control-queue request/response round trip, blocking the caller
+    /// (without spinning) until `handle_ctl_irq` observes the response come back.
+    pub fn send<T: Pod>(&self, data: &T, send_size: usize, recv_size: usize) {
+        let ctl_slice = {
+            let req_slice =
+                DmaStreamSlice::new(self.ctl_buffer.clone(), 0, send_size);
+            req_slice.write_val(0, data).unwrap();
+            req_slice.sync().unwrap();
+            req_slice
+        };
+
+        let event_slice = {
+            let resp_slice =
+                DmaStreamSlice::new(self.event_buffer.clone(), 0, recv_size);
+            resp_slice
+        };
+
+        let head = {
+            let mut queue = self.queue.disable_irq().lock();
+            let head = queue
+                .add_dma_buf(&[&ctl_slice], &[&event_slice])
+                .expect("add queue failed");
+            if queue.should_notify() {
+                queue.notify();
+            }
+            head
+        };
+
+        self.ctl_waiter.wait_for(head);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndPcmQueryInfo {
+    pub hdr: u32,    // 通用信息头
+    pub start_id: u32,        // 小端：起始 ID
+    pub count: u32,           // 小端：查询的条目数量
+    pub size: u32,            // 小端：每个条目的大小
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndPcmInfo {
+    pub hdr: u32,   // 嵌套的通用信息头
+    pub features: u32,        // 小端：特性位掩码 (1 << VIRTIO_SND_PCM_F_XXX)
+    pub formats: u64,         // 小端：支持的采样格式 (1 << VIRTIO_SND_PCM_FMT_XXX)
+    pub rates: u64,           // 小端：支持的采样率 (1 << VIRTIO_SND_PCM_RATE_XXX)
+    pub direction: u8,        // 数据流方向 (VIRTIO_SND_D_XXX)
+    pub channels_min: u8,     // 支持的最小通道数
+    pub channels_max: u8,     // 支持的最大通道数
+    pub padding: [u8; 5],     // 填充字节
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndPcmSetParams {
+    pub hdr: u32,           // 头部，表示结构体的类型或标识符 (VIRTIO_SND_R_PCM_SET_PARAMS)
+    pub stream_id: u32,     // 小端：PCM 流 ID
+    pub buffer_bytes: u32,  // 缓冲区大小，单位字节
+    pub period_bytes: u32,  // 每个周期的字节数
+    pub features: u32,      // 特性标志位掩码 (1 << VIRTIO_SND_PCM_F_XXX)
+    pub channels: u8,       // 音频通道数
+    pub format: u8,         // 音频格式 (VIRTIO_SND_PCM_FMT_XXX)
+    pub rate: u8,           // 采样率 (VIRTIO_SND_PCM_RATE_XXX)
+    pub padding: [u8; 1],   // 填充字节，用于对齐 (24 字节，匹配 virtio_snd_pcm_set_params)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndPcmHdr {
+    pub hdr: u32, // 通用信息头
+    pub stream_id: u32,    // 小端：PCM 流 ID
+}
+
+/// Device-readable IO message header, sent ahead of the raw PCM payload on `txq`/`rxq`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndPcmIOHeader {
+    pub stream_id: u32,
+}
+
+/// Device-writable IO message tail, appended after the PCM payload descriptor(s).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndPcmIOStatus {
+    pub status: u32,
+    pub latency_bytes: u32,
+}
+
+const PCM_HDR_SIZE: usize = size_of::<SndPcmHdr>();
+const PCM_SET_PARAMS_SIZE: usize = size_of::<SndPcmSetParams>();
+const PCM_IO_HEADER_SIZE: usize = size_of::<SndPcmIOHeader>();
+const PCM_IO_STATUS_SIZE: usize = size_of::<SndPcmIOStatus>();
+
+/// Decodes a fixed-size, NUL-terminated (or NUL-padded) byte array as reported
+/// by the device into an owned `String`.
+fn parse_c_str(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcm_wire_structs_match_spec_layout() {
+        // `virtio_snd_pcm_set_params`: hdr + stream_id + buffer/period bytes
+        // (4 x u32) + features (u32) + channels/format/rate (3 x u8) + 1 byte
+        // padding = 24 bytes.
+        assert_eq!(size_of::<SndPcmSetParams>(), 24);
+        assert_eq!(size_of::<SndPcmHdr>(), 8);
+        assert_eq!(size_of::<SndPcmIOHeader>(), 4);
+        assert_eq!(size_of::<SndPcmIOStatus>(), 8);
+    }
+
+    #[test]
+    fn status_hdr_size_matches_virtio_snd_hdr() {
+        assert_eq!(STATUS_HDR_SIZE, size_of::<u32>());
+    }
+}
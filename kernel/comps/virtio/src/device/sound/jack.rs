@@ -0,0 +1,84 @@
+use ostd::Pod;
+
+/// Parsed, ergonomic view of a `virtio_snd_jack_info` entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SndJackInfo {
+    pub jack_id: u32,
+    pub features: u32,
+    pub hda_fn_nid: u32,
+    pub connected: bool,
+}
+
+/// Callback invoked when a jack's connected state changes, with `(jack_id, connected)`.
+pub type JackCallback = dyn Fn(u32, bool) + Send + Sync;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndJackHdr {
+    pub hdr: u32,     // 通用信息头
+    pub jack_id: u32, // 小端：插孔 ID
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndJackQueryInfo {
+    pub hdr: u32,      // 通用信息头
+    pub start_id: u32, // 小端：起始 ID
+    pub count: u32,    // 小端：查询的条目数量
+    pub size: u32,     // 小端：每个条目的大小
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndJackInfoRaw {
+    pub hda_fn_nid: u32,        // 小端：对应的 HDA 功能组节点 ID (virtio_snd_info::hda_fn_nid)
+    pub features: u32,          // 小端：特性位掩码 (1 << VIRTIO_SND_JACK_F_XXX)
+    pub hda_reg_defconf: u32,   // 小端：HDA pin 默认配置寄存器
+    pub hda_reg_caps: u32,      // 小端：HDA pin 能力寄存器
+    pub connected: u8,          // 当前是否已连接
+    pub padding: [u8; 7],       // 填充字节
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod)]
+pub struct SndJackRemap {
+    pub hdr: SndJackHdr, // jack_id 所在的通用头
+    pub association: u32, // 小端：重映射到的插孔关联
+    pub sequence: u32,     // 小端：重映射到的插孔序列号
+}
+
+pub(super) const JACK_INFO_SIZE: usize = size_of::<SndJackInfoRaw>();
+pub(super) const JACK_QUERY_INFO_SIZE: usize = size_of::<SndJackQueryInfo>();
+pub(super) const JACK_HDR_SIZE: usize = size_of::<SndJackHdr>();
+pub(super) const JACK_REMAP_SIZE: usize = size_of::<SndJackRemap>();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jack_info_raw_matches_wire_layout() {
+        // `virtio_snd_jack_info`: 4 x u32 + connected (u8) + 7 bytes padding = 24 bytes.
+        assert_eq!(size_of::<SndJackInfoRaw>(), 24);
+    }
+
+    #[test]
+    fn jack_info_raw_parses_after_status_hdr_skip() {
+        let raw = SndJackInfoRaw {
+            hda_fn_nid: 1,
+            features: 2,
+            hda_reg_defconf: 3,
+            hda_reg_caps: 4,
+            connected: 1,
+            padding: [0; 7],
+        };
+
+        let mut buf = [0u8; 4 + JACK_INFO_SIZE];
+        buf[4..].copy_from_slice(raw.as_bytes());
+
+        let parsed = SndJackInfoRaw::from_bytes(&buf[4..]);
+        assert_eq!(parsed.hda_fn_nid, raw.hda_fn_nid);
+        assert_eq!(parsed.features, raw.features);
+        assert_eq!(parsed.connected, raw.connected);
+    }
+}
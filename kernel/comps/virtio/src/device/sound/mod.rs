@@ -0,0 +1,10 @@
+pub mod config;
+pub mod ctl;
+pub mod device;
+pub mod jack;
+pub mod stream;
+
+pub use ctl::SndCtlInfo;
+pub use device::SoundDevice;
+pub use jack::{JackCallback, SndJackInfo};
+pub use stream::{Stream, StreamDirection};
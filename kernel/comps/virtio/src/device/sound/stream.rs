@@ -0,0 +1,78 @@
+use alloc::sync::Arc;
+
+use crate::device::sound::config::MessageHdr;
+use crate::device::sound::device::{SndPcmSetParams, SoundCallback, SoundDevice};
+
+/// Data flow direction of a PCM stream (`VIRTIO_SND_D_XXX`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamDirection {
+    Output,
+    Input,
+}
+
+/// A single PCM stream opened on a [`SoundDevice`], driving it through the
+/// `SetParams -> Prepare -> Start -> IO -> Stop -> Release` life cycle.
+pub struct Stream {
+    device: Arc<SoundDevice>,
+    stream_id: u32,
+    direction: StreamDirection,
+}
+
+impl Stream {
+    pub(super) fn new(device: Arc<SoundDevice>, stream_id: u32, direction: StreamDirection) -> Self {
+        Self {
+            device,
+            stream_id,
+            direction,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.stream_id
+    }
+
+    pub fn direction(&self) -> StreamDirection {
+        self.direction
+    }
+
+    /// Negotiates the PCM parameters for this stream and allocates its period buffer ring.
+    pub fn set_params(&self, params: SndPcmSetParams) {
+        self.device
+            .pcm_set_params(self.stream_id, self.direction, params);
+    }
+
+    pub fn prepare(&self) {
+        self.device.pcm_control(self.stream_id, MessageHdr::PcmPrepare);
+    }
+
+    pub fn start(&self) {
+        self.device.pcm_control(self.stream_id, MessageHdr::PcmStart);
+    }
+
+    pub fn stop(&self) {
+        self.device.pcm_control(self.stream_id, MessageHdr::PcmStop);
+    }
+
+    pub fn release(&self) {
+        self.device.pcm_control(self.stream_id, MessageHdr::PcmRelease);
+    }
+
+    /// Submits one period of playback data and returns the device-reported `latency_bytes`.
+    pub fn write_period(&self, pcm: &[u8]) -> u32 {
+        debug_assert_eq!(self.direction, StreamDirection::Output);
+        self.device.pcm_write_period(self.stream_id, pcm)
+    }
+
+    /// Fills `pcm` with one period of captured data and returns the device-reported `latency_bytes`.
+    pub fn read_period(&self, pcm: &mut [u8]) -> u32 {
+        debug_assert_eq!(self.direction, StreamDirection::Input);
+        self.device.pcm_read_period(self.stream_id, pcm)
+    }
+
+    /// Registers the callback invoked (off the hard IRQ path, from `run_period_worker`)
+    /// whenever the device reports a `PcmPeriodElapsed` for this stream.
+    pub fn set_callback(&self, callback: Arc<SoundCallback>) {
+        self.device
+            .register_stream_callback(self.stream_id, callback);
+    }
+}